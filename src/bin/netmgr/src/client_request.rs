@@ -33,7 +33,6 @@ pub async fn handle_client_request(
             let buffer = handle.get_incoming_buffer(data);
             #[allow(unreachable_code)]
             let _ = ipv4::send_to(
-                handle,
                 Ipv4Addr::localhost(), /* TODO */
                 addr,
                 todo!(),
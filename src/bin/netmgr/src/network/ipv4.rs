@@ -77,7 +77,6 @@ fn build_ipv4_header(source: Ipv4Addr, dest: Ipv4Addr, prot: Ipv4Prot) -> Ipv4He
 }
 
 pub async fn send_to(
-    _handle: &HandleRef,
     source: Ipv4Addr,
     dest: Ipv4Addr,
     prot: Ipv4Prot,
@@ -1,5 +1,9 @@
-use std::sync::Arc;
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
+use byteorder::{ByteOrder, NetworkEndian};
 use twizzler_async::Task;
 use twizzler_net::{
     addr::{Ipv4Addr, NodeAddr, ServiceAddr},
@@ -44,6 +48,11 @@ pub fn init() -> (ServiceAddr, Icmp) {
     todo!()
 }
 
+#[allow(dead_code)]
+const ICMP_TIMESTAMP: u8 = 13;
+#[allow(dead_code)]
+const ICMP_TIMESTAMP_REPLY: u8 = 14;
+
 #[repr(C)]
 struct IcmpHeader {
     ty: u8,
@@ -52,18 +61,138 @@ struct IcmpHeader {
     extra: [u8; 4],
 }
 
+// One's complement sum (RFC 1071) of the chunks, treated as a single big-endian byte stream. A
+// message with a valid internet checksum sums to 0xffff.
+fn ones_complement_sum<'a>(chunks: impl IntoIterator<Item = &'a [u8]>) -> u16 {
+    let mut sum = 0u32;
+    let mut odd = None;
+    for chunk in chunks {
+        for &byte in chunk {
+            match odd.take() {
+                Some(hi) => sum += u16::from_be_bytes([hi, byte]) as u32,
+                None => odd = Some(byte),
+            }
+        }
+    }
+    if let Some(hi) = odd {
+        sum += u16::from_be_bytes([hi, 0]) as u32;
+    }
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    sum as u16
+}
+
+impl IcmpHeader {
+    // Compute the internet checksum over this header followed by the payload chunks.
+    fn compute_csum(&mut self, payload: &[&[u8]]) {
+        self.csum = [0; 2];
+        let sum =
+            ones_complement_sum(core::iter::once(self.as_bytes()).chain(payload.iter().copied()));
+        NetworkEndian::write_u16(&mut self.csum, !sum);
+    }
+}
+
 impl Header for IcmpHeader {
     fn len(&self) -> usize {
         8
     }
 
-    fn update_csum(
-        &mut self,
-        _header_buffer: NicBuffer,
-        _buffers: &[crate::link::nic::SendableBuffer],
-    ) {
-        todo!()
+    fn update_csum(&mut self, _header_buffer: NicBuffer, buffers: &[SendableBuffer]) {
+        let payload: Vec<&[u8]> = buffers.iter().map(|b| b.as_bytes()).collect();
+        self.compute_csum(&payload);
+    }
+}
+
+// Timestamp request and reply messages carry three 32-bit timestamps after the header, while the
+// identifier and sequence number live in the header's extra field.
+#[allow(dead_code)]
+#[repr(C)]
+struct IcmpTimestamp {
+    originate: [u8; 4],
+    receive: [u8; 4],
+    transmit: [u8; 4],
+}
+
+impl IcmpTimestamp {
+    #[allow(dead_code)]
+    fn as_bytes(&self) -> &[u8] {
+        let ptr = self as *const Self as *const u8;
+        // SAFETY: the struct is repr(C) with only [u8; 4] fields, so it has no padding and every
+        // byte is initialized.
+        unsafe { core::slice::from_raw_parts(ptr, core::mem::size_of::<Self>()) }
+    }
+}
+
+// Milliseconds since midnight UT, as ICMP timestamps expect.
+#[allow(dead_code)]
+fn icmp_time_now() -> u32 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    (now.as_millis() % (24 * 60 * 60 * 1000)) as u32
+}
+
+// Build the reply to an ICMP timestamp request, given the request's ICMP header and payload.
+// Returns None if the packet is not a well-formed timestamp request.
+#[allow(dead_code)]
+fn timestamp_reply(packet: &[u8], now: u32) -> Option<(IcmpHeader, SendableBuffer<'static>)> {
+    let hdr_len = core::mem::size_of::<IcmpHeader>();
+    if packet.len() < hdr_len + core::mem::size_of::<IcmpTimestamp>()
+        || packet[0] != ICMP_TIMESTAMP
+        || packet[1] != 0
+        || ones_complement_sum([packet]) != 0xffff
+    {
+        return None;
     }
+    let mut header = IcmpHeader {
+        ty: ICMP_TIMESTAMP_REPLY,
+        code: 0,
+        csum: [0; 2],
+        extra: packet[4..8].try_into().unwrap(),
+    };
+    let mut payload = IcmpTimestamp {
+        originate: packet[hdr_len..(hdr_len + 4)].try_into().unwrap(),
+        receive: [0; 4],
+        transmit: [0; 4],
+    };
+    NetworkEndian::write_u32(&mut payload.receive, now);
+    NetworkEndian::write_u32(&mut payload.transmit, now);
+    let mut payload_buffer = NicBuffer::allocate(core::mem::size_of::<IcmpTimestamp>());
+    payload_buffer
+        .as_bytes_mut()
+        .copy_from_slice(payload.as_bytes());
+    let payload = SendableBuffer::NicBuffer(payload_buffer);
+    header.update_csum(
+        NicBuffer::allocate(header.len()),
+        core::slice::from_ref(&payload),
+    );
+    Some((header, payload))
+}
+
+// Timestamp requests are answered by netmgr itself, regardless of which endpoints are listening.
+// Only loopback requests are answered for now, since ipv4::send_to can't route anywhere else.
+#[allow(dead_code)]
+fn respond_to_timestamp(packet: &[u8], source_addr: Ipv4Addr, dest_addr: Ipv4Addr) -> bool {
+    if !source_addr.is_localhost() {
+        return false;
+    }
+    let Some((header, payload)) = timestamp_reply(packet, icmp_time_now()) else {
+        return false;
+    };
+    Task::spawn(async move {
+        let _ = crate::network::ipv4::send_to(
+            dest_addr,
+            source_addr,
+            Ipv4Prot::Icmp,
+            &[payload],
+            NicBuffer::allocate(0x1000),
+            Some(&header),
+        )
+        .await;
+    })
+    .detach();
+    true
 }
 
 pub fn _handle_icmp_packet(
@@ -76,6 +205,11 @@ pub fn _handle_icmp_packet(
     let header = unsafe { packet.get_minimal_header::<IcmpHeader>(packet_start) };
     println!("got icmp packet {} {}", header.ty, header.code);
 
+    let bytes = &packet.as_bytes()[packet_start..(packet_start + packet_len_inc_hdr)];
+    if respond_to_timestamp(bytes, source_addr, dest_addr) {
+        return;
+    }
+
     let info = EndPointKey::new(
         NodeAddr::Ipv4(source_addr),
         NodeAddr::Ipv4(dest_addr),
@@ -113,7 +247,6 @@ pub async fn _send_packet(
     Task::spawn(async move {
         let buffer = handle.get_incoming_buffer(packet_data);
         let _ = crate::network::ipv4::send_to(
-            &handle,
             source,
             dest_addr,
             Ipv4Prot::Icmp,
@@ -126,3 +259,75 @@ pub async fn _send_packet(
     .detach();
     TxCompletion::Nothing
 }
+
+#[cfg(test)]
+mod tests {
+    use byteorder::{ByteOrder, NetworkEndian};
+    use twizzler_net::addr::Ipv4Addr;
+
+    use super::{
+        ones_complement_sum, respond_to_timestamp, timestamp_reply, ICMP_TIMESTAMP,
+        ICMP_TIMESTAMP_REPLY,
+    };
+    use crate::header::Header;
+
+    fn set_csum(request: &mut [u8; 20]) {
+        request[2..4].copy_from_slice(&[0; 2]);
+        let sum = ones_complement_sum([&request[..]]);
+        NetworkEndian::write_u16(&mut request[2..4], !sum);
+    }
+
+    fn timestamp_request() -> [u8; 20] {
+        let mut request = [0u8; 20];
+        request[0] = ICMP_TIMESTAMP;
+        request[4..8].copy_from_slice(&[0x12, 0x34, 0x00, 0x01]);
+        NetworkEndian::write_u32(&mut request[8..12], 1000);
+        set_csum(&mut request);
+        request
+    }
+
+    #[test]
+    fn timestamp_request_gets_reply() {
+        let mut request = timestamp_request();
+
+        let (header, payload_buffer) = timestamp_reply(&request, 2000).unwrap();
+        let payload = payload_buffer.as_bytes();
+        assert_eq!(header.ty, ICMP_TIMESTAMP_REPLY);
+        assert_eq!(header.code, 0);
+        assert_eq!(header.extra, [0x12, 0x34, 0x00, 0x01]);
+        assert_eq!(NetworkEndian::read_u32(&payload[0..4]), 1000);
+        assert_eq!(NetworkEndian::read_u32(&payload[4..8]), 2000);
+        assert_eq!(NetworkEndian::read_u32(&payload[8..12]), 2000);
+
+        // A valid checksum makes the one's complement sum of the whole message come out to zero.
+        let mut sum = 0u32;
+        let reply = [header.as_bytes(), payload].concat();
+        for word in reply.chunks(2) {
+            sum += NetworkEndian::read_u16(word) as u32;
+        }
+        while sum >> 16 != 0 {
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+        assert_eq!(sum, 0xffff);
+
+        // A corrupted request fails its checksum and gets no reply.
+        request[9] ^= 0x01;
+        assert!(timestamp_reply(&request, 2000).is_none());
+
+        let mut request = timestamp_request();
+        request[0] = 8;
+        set_csum(&mut request);
+        assert!(timestamp_reply(&request, 2000).is_none());
+    }
+
+    #[test]
+    fn non_loopback_timestamp_request_is_not_answered() {
+        let request = timestamp_request();
+        let remote = Ipv4Addr::from(0x0a00_0002);
+        assert!(!respond_to_timestamp(
+            &request,
+            remote,
+            Ipv4Addr::localhost()
+        ));
+    }
+}